@@ -1,4 +1,6 @@
 pub mod multicast_service;
+pub mod rpc;
+pub mod transport;
 
 use std::{
     ffi::CStr,
@@ -73,6 +75,64 @@ pub extern "C" fn discovery_get_peers_json(handle: *mut DiscoveryHandle, out_ptr
     0
 }
 
+#[no_mangle]
+pub extern "C" fn discovery_get_public_key(handle: *mut DiscoveryHandle, out_pubkey: *mut u8) -> i32 {
+    if handle.is_null() || out_pubkey.is_null() { return -1; }
+    let dh = unsafe { &*handle };
+    let pubkey = dh.discovery.public_key();
+    unsafe { ptr::copy_nonoverlapping(pubkey.as_ptr(), out_pubkey, pubkey.len()); }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn discovery_get_node_id(handle: *mut DiscoveryHandle) -> u64 {
+    if handle.is_null() { return 0; }
+    let dh = unsafe { &*handle };
+    dh.discovery.node_id()
+}
+
+#[no_mangle]
+pub extern "C" fn discovery_send_request(
+    handle: *mut DiscoveryHandle,
+    peer_pubkey: *const u8,
+    endpoint_id: u16,
+    payload_ptr: *const u8,
+    payload_len: usize,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    if handle.is_null() || peer_pubkey.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return -1;
+    }
+    if payload_len > 0 && payload_ptr.is_null() {
+        return -1;
+    }
+    let dh = unsafe { &*handle };
+
+    let mut pubkey = [0u8; 32];
+    unsafe { ptr::copy_nonoverlapping(peer_pubkey, pubkey.as_mut_ptr(), 32) };
+    let payload = if payload_len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(payload_ptr, payload_len).to_vec() }
+    };
+
+    let result = runtime().block_on(dh.discovery.send_request(pubkey, endpoint_id, payload));
+    match result {
+        Ok(mut bytes) => {
+            let len = bytes.len();
+            let ptr_data = bytes.as_mut_ptr();
+            std::mem::forget(bytes);
+            unsafe {
+                *out_ptr = ptr_data;
+                *out_len = len;
+            }
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn discovery_free_buf(ptr_data: *mut u8, len: usize) {
     if ptr_data.is_null() { return; }
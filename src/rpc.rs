@@ -0,0 +1,217 @@
+//! A small request/response messaging layer on top of discovered peers,
+//! modeled on netapp's `proto`/`message` modules: a length-prefixed frame
+//! carries an endpoint id and a request id, connections are opened lazily
+//! and reused, and a background task demultiplexes responses back to the
+//! caller awaiting them.
+
+use crate::multicast_service::PubKey;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{oneshot, Mutex, RwLock},
+};
+
+pub type EndpointId = u16;
+pub type RequestId = u32;
+
+/// A handler registered for an endpoint: takes the request body, returns
+/// the response body.
+pub type Handler = Arc<dyn Fn(Vec<u8>) -> Vec<u8> + Send + Sync>;
+
+const FRAME_REQUEST: u8 = 0;
+const FRAME_RESPONSE: u8 = 1;
+
+struct Frame {
+    endpoint: EndpointId,
+    request_id: RequestId,
+    kind: u8,
+    body: Vec<u8>,
+}
+
+impl Frame {
+    async fn write_to<W: AsyncWriteExt + Unpin>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u16(self.endpoint).await?;
+        w.write_u32(self.request_id).await?;
+        w.write_u8(self.kind).await?;
+        w.write_u32(self.body.len() as u32).await?;
+        w.write_all(&self.body).await?;
+        w.flush().await
+    }
+
+    async fn read_from<R: AsyncReadExt + Unpin>(r: &mut R) -> io::Result<Self> {
+        let endpoint = r.read_u16().await?;
+        let request_id = r.read_u32().await?;
+        let kind = r.read_u8().await?;
+        let len = r.read_u32().await? as usize;
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body).await?;
+        Ok(Self { endpoint, request_id, kind, body })
+    }
+}
+
+/// One outbound connection to a peer. Concurrent requests over it are
+/// matched to their responses by `request_id`.
+struct Connection {
+    write_half: Mutex<OwnedWriteHalf>,
+    pending: Mutex<HashMap<RequestId, oneshot::Sender<Vec<u8>>>>,
+    next_request_id: AtomicU32,
+}
+
+impl Connection {
+    async fn open(addr: SocketAddr) -> io::Result<Arc<Self>> {
+        let (read_half, write_half) = TcpStream::connect(addr).await?.into_split();
+        let conn = Arc::new(Self {
+            write_half: Mutex::new(write_half),
+            pending: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU32::new(1),
+        });
+
+        let reader_conn = conn.clone();
+        tokio::spawn(async move {
+            reader_conn.read_loop(read_half).await;
+        });
+
+        Ok(conn)
+    }
+
+    async fn read_loop(self: Arc<Self>, mut read_half: OwnedReadHalf) {
+        loop {
+            match Frame::read_from(&mut read_half).await {
+                Ok(frame) if frame.kind == FRAME_RESPONSE => {
+                    if let Some(tx) = self.pending.lock().await.remove(&frame.request_id) {
+                        let _ = tx.send(frame.body);
+                    }
+                }
+                Ok(_) => {} // a reply connection shouldn't see requests
+                Err(_) => {
+                    // Drop every pending sender so awaiting callers see the
+                    // connection close instead of waiting forever.
+                    self.pending.lock().await.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn request(&self, endpoint: EndpointId, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(request_id, tx);
+
+        let frame = Frame { endpoint, request_id, kind: FRAME_REQUEST, body };
+        if let Err(e) = frame.write_to(&mut *self.write_half.lock().await).await {
+            self.pending.lock().await.remove(&request_id);
+            return Err(e.into());
+        }
+
+        let result = rx.await;
+        if result.is_err() {
+            // The read loop hit EOF/an error and dropped `tx` without ever
+            // answering us; it won't come back to clean up this slot, so we
+            // have to.
+            self.pending.lock().await.remove(&request_id);
+        }
+        result.map_err(|_| anyhow::anyhow!("connection closed before a response arrived"))
+    }
+}
+
+/// Owns the registered endpoint handlers and the pool of outbound
+/// connections to other peers. One instance lives on `LanDiscovery`.
+pub struct RpcState {
+    handlers: RwLock<HashMap<EndpointId, Handler>>,
+    connections: RwLock<HashMap<PubKey, Arc<Connection>>>,
+}
+
+impl RpcState {
+    pub fn new() -> Self {
+        Self {
+            handlers: RwLock::new(HashMap::new()),
+            connections: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn register_handler(&self, endpoint: EndpointId, handler: Handler) {
+        self.handlers.write().await.insert(endpoint, handler);
+    }
+
+    /// Accept loop for incoming requests from other peers.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _addr)) => {
+                    let state = self.clone();
+                    tokio::spawn(async move { state.serve_connection(stream).await });
+                }
+                Err(e) => eprintln!("RPC accept error: {:?}", e),
+            }
+        }
+    }
+
+    async fn serve_connection(self: Arc<Self>, stream: TcpStream) {
+        let (mut read_half, write_half) = stream.into_split();
+        let write_half = Arc::new(Mutex::new(write_half));
+        loop {
+            let frame = match Frame::read_from(&mut read_half).await {
+                Ok(f) => f,
+                Err(_) => break,
+            };
+            if frame.kind != FRAME_REQUEST {
+                continue;
+            }
+
+            let handler = self.handlers.read().await.get(&frame.endpoint).cloned();
+            let write_half = write_half.clone();
+            tokio::spawn(async move {
+                let body = handler.map_or_else(Vec::new, |h| h(frame.body));
+                let response = Frame {
+                    endpoint: frame.endpoint,
+                    request_id: frame.request_id,
+                    kind: FRAME_RESPONSE,
+                    body,
+                };
+                let _ = response.write_to(&mut *write_half.lock().await).await;
+            });
+        }
+    }
+
+    /// Open (or reuse) a connection to `peer_id` at `addr` and issue a
+    /// request, returning the matching response body.
+    pub async fn send_request(
+        &self,
+        peer_id: PubKey,
+        addr: SocketAddr,
+        endpoint: EndpointId,
+        payload: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let existing = self.connections.read().await.get(&peer_id).cloned();
+        let conn = match existing {
+            Some(conn) => conn,
+            None => {
+                let conn = Connection::open(addr).await?;
+                self.connections.write().await.insert(peer_id, conn.clone());
+                conn
+            }
+        };
+
+        match conn.request(endpoint, payload).await {
+            Ok(body) => Ok(body),
+            Err(e) => {
+                // Connection is presumably dead; drop it so the next call
+                // dials a fresh one instead of failing forever.
+                self.connections.write().await.remove(&peer_id);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Pluggable transports for the discovery/gossip channel.
+//!
+//! `LanDiscovery` used to hardcode IPv4 multicast. The `Transport` trait
+//! generalizes "broadcast an announcement" / "unicast a gossip message" /
+//! "receive the next datagram" so the same announcer/listener/gossip logic
+//! can run over IPv4 multicast, IPv6 multicast, or (for tests) an
+//! in-process Unix-datagram loopback that needs no real NIC.
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::net::{UdpSocket, UnixDatagram};
+
+const MULTICAST_V4_ADDR: &str = "239.255.255.250";
+const MULTICAST_V6_ADDR: &str = "ff02::c";
+/// UDP port the discovery/gossip transports listen on. Exposed so callers
+/// can turn an observed peer address into "where their discovery listener
+/// actually is" (an announce is sent from an ephemeral source port, not
+/// this one).
+pub const MULTICAST_PORT: u16 = 9999;
+
+/// Where a datagram came from / where to send one, independent of the
+/// underlying transport. Gossiped alongside records (see
+/// `GossipMessage::Response`/`Records` in `multicast_service`) so a peer
+/// learned about transitively still gets a dialable address, hence
+/// `Serialize`/`Deserialize`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransportAddr {
+    Ip(SocketAddr),
+    Unix(String),
+}
+
+/// Which transport `LanDiscovery::with_transport` should bind
+/// (`LanDiscovery::new` always uses `MulticastV4`).
+pub enum TransportConfig {
+    MulticastV4,
+    MulticastV6 { interface_index: u32 },
+    /// In-process loopback for tests: every transport bound with the same
+    /// `namespace` can see each other's group sends, over Unix datagram
+    /// sockets in the temp directory rather than a network interface.
+    UnixLoopback { namespace: String },
+}
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Broadcast `data` to every peer listening on the group (multicast
+    /// address, or every member of a Unix-loopback namespace).
+    async fn send_group(&self, data: &[u8]) -> io::Result<()>;
+    /// Unicast `data` directly to one previously-seen address.
+    async fn send_to(&self, target: &TransportAddr, data: &[u8]) -> io::Result<()>;
+    /// Receive the next inbound datagram and who it came from.
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, TransportAddr)>;
+}
+
+/// IPv4 multicast at 239.255.255.250:9999 — the transport this crate always
+/// used before transports were pluggable.
+pub struct Ipv4MulticastTransport {
+    announce_socket: UdpSocket,
+    listen_socket: UdpSocket,
+    group: SocketAddr,
+}
+
+impl Ipv4MulticastTransport {
+    pub async fn bind(local_ip: Ipv4Addr) -> anyhow::Result<Self> {
+        let multicast: Ipv4Addr = MULTICAST_V4_ADDR.parse()?;
+
+        let announce_socket = {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_address(true)?;
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_ttl_v4(1)?;
+            let bind_addr = SocketAddr::new(IpAddr::V4(local_ip), 0);
+            socket.bind(&bind_addr.into())?;
+            socket.set_multicast_if_v4(&local_ip)?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket.into())?
+        };
+
+        let listen_socket = {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_address(true)?;
+            #[cfg(unix)]
+            socket.set_reuse_port(true).ok();
+            let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MULTICAST_PORT);
+            socket.bind(&bind_addr.into())?;
+            socket.join_multicast_v4(&multicast, &local_ip)?;
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_ttl_v4(1)?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket.into())?
+        };
+
+        Ok(Self {
+            announce_socket,
+            listen_socket,
+            group: SocketAddr::new(IpAddr::V4(multicast), MULTICAST_PORT),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for Ipv4MulticastTransport {
+    async fn send_group(&self, data: &[u8]) -> io::Result<()> {
+        self.announce_socket.send_to(data, self.group).await.map(|_| ())
+    }
+
+    async fn send_to(&self, target: &TransportAddr, data: &[u8]) -> io::Result<()> {
+        match target {
+            TransportAddr::Ip(addr) => self.listen_socket.send_to(data, addr).await.map(|_| ()),
+            TransportAddr::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unix address on an IPv4 transport",
+            )),
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, TransportAddr)> {
+        let (len, src) = self.listen_socket.recv_from(buf).await?;
+        Ok((len, TransportAddr::Ip(src)))
+    }
+}
+
+/// IPv6 link-local multicast at [ff02::c]:9999, scoped to one interface.
+pub struct Ipv6MulticastTransport {
+    announce_socket: UdpSocket,
+    listen_socket: UdpSocket,
+    group: SocketAddr,
+}
+
+impl Ipv6MulticastTransport {
+    pub async fn bind(interface_index: u32) -> anyhow::Result<Self> {
+        let multicast: Ipv6Addr = MULTICAST_V6_ADDR.parse()?;
+
+        let announce_socket = {
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_address(true)?;
+            socket.set_multicast_loop_v6(true)?;
+            let bind_addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+            socket.bind(&bind_addr.into())?;
+            socket.set_multicast_if_v6(interface_index)?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket.into())?
+        };
+
+        let listen_socket = {
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_reuse_address(true)?;
+            #[cfg(unix)]
+            socket.set_reuse_port(true).ok();
+            let bind_addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), MULTICAST_PORT);
+            socket.bind(&bind_addr.into())?;
+            socket.join_multicast_v6(&multicast, interface_index)?;
+            socket.set_multicast_loop_v6(true)?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket.into())?
+        };
+
+        Ok(Self {
+            announce_socket,
+            listen_socket,
+            group: SocketAddr::new(IpAddr::V6(multicast), MULTICAST_PORT),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for Ipv6MulticastTransport {
+    async fn send_group(&self, data: &[u8]) -> io::Result<()> {
+        self.announce_socket.send_to(data, self.group).await.map(|_| ())
+    }
+
+    async fn send_to(&self, target: &TransportAddr, data: &[u8]) -> io::Result<()> {
+        match target {
+            TransportAddr::Ip(addr) => self.listen_socket.send_to(data, addr).await.map(|_| ()),
+            TransportAddr::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unix address on an IPv6 transport",
+            )),
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, TransportAddr)> {
+        let (len, src) = self.listen_socket.recv_from(buf).await?;
+        Ok((len, TransportAddr::Ip(src)))
+    }
+}
+
+/// In-process "multicast group" keyed by namespace, so `UnixLoopbackTransport`
+/// can fan a group send out to every other member bound in the same test
+/// process without any real networking.
+static NAMESPACE_MEMBERS: Lazy<Mutex<HashMap<String, Vec<String>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_SOCKET_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct UnixLoopbackTransport {
+    socket: UnixDatagram,
+    path: String,
+    namespace: String,
+}
+
+impl UnixLoopbackTransport {
+    pub fn bind(namespace: &str) -> anyhow::Result<Self> {
+        let id = NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir()
+            .join(format!("lan-discovery-{}-{}.sock", namespace, id))
+            .to_string_lossy()
+            .into_owned();
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+
+        NAMESPACE_MEMBERS
+            .lock()
+            .unwrap()
+            .entry(namespace.to_string())
+            .or_default()
+            .push(path.clone());
+
+        Ok(Self { socket, path, namespace: namespace.to_string() })
+    }
+}
+
+impl Drop for UnixLoopbackTransport {
+    fn drop(&mut self) {
+        if let Some(members) = NAMESPACE_MEMBERS.lock().unwrap().get_mut(&self.namespace) {
+            members.retain(|p| p != &self.path);
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[async_trait]
+impl Transport for UnixLoopbackTransport {
+    async fn send_group(&self, data: &[u8]) -> io::Result<()> {
+        let members = NAMESPACE_MEMBERS
+            .lock()
+            .unwrap()
+            .get(&self.namespace)
+            .cloned()
+            .unwrap_or_default();
+        for member in members {
+            if member == self.path {
+                continue; // don't hear our own announcements
+            }
+            let _ = self.socket.send_to(data, &member).await;
+        }
+        Ok(())
+    }
+
+    async fn send_to(&self, target: &TransportAddr, data: &[u8]) -> io::Result<()> {
+        match target {
+            TransportAddr::Unix(path) => self.socket.send_to(data, path).await.map(|_| ()),
+            TransportAddr::Ip(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ip address on a unix-loopback transport",
+            )),
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, TransportAddr)> {
+        let (len, addr) = self.socket.recv_from(buf).await?;
+        let path = addr
+            .as_pathname()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok((len, TransportAddr::Unix(path)))
+    }
+}
+
+/// Pick the index of the first non-loopback interface that has an IPv6
+/// address, for `set_multicast_if_v6`/`join_multicast_v6`. Falls back to 0
+/// (let the OS choose) if none can be determined.
+#[cfg(unix)]
+pub fn default_ipv6_interface_index() -> u32 {
+    let Ok(addrs) = local_ip_address::list_afinet_netifas() else { return 0 };
+    for (iface, ip) in &addrs {
+        if let IpAddr::V6(v6) = ip {
+            if v6.is_loopback() {
+                continue;
+            }
+            if let Ok(cname) = std::ffi::CString::new(iface.as_str()) {
+                let index = unsafe { libc::if_nametoindex(cname.as_ptr()) };
+                if index != 0 {
+                    return index;
+                }
+            }
+        }
+    }
+    0
+}
+
+/// Find the IPv6 address bound to interface `interface_index`, for
+/// advertising in `Announcement.ip` so a peer that learns about us through
+/// gossip (not direct multicast) can still dial our RPC port. Returns `None`
+/// if the interface can't be resolved (including index 0, "let the OS
+/// choose"), in which case the caller has no reachable address to advertise.
+#[cfg(unix)]
+pub fn resolve_ipv6_address(interface_index: u32) -> Option<Ipv6Addr> {
+    if interface_index == 0 {
+        return None;
+    }
+    let addrs = local_ip_address::list_afinet_netifas().ok()?;
+    for (iface, ip) in &addrs {
+        if let IpAddr::V6(v6) = ip {
+            if v6.is_loopback() {
+                continue;
+            }
+            if let Ok(cname) = std::ffi::CString::new(iface.as_str()) {
+                let index = unsafe { libc::if_nametoindex(cname.as_ptr()) };
+                if index == interface_index {
+                    return Some(*v6);
+                }
+            }
+        }
+    }
+    None
+}